@@ -0,0 +1,158 @@
+//! Canonical application-level wire format shared by `Listener` and `Transmitter`.
+//!
+//! Conceptually these types are generated from a single schema definition (in the
+//! spirit of preserves-schema's `.prs` build-time codegen): one place defines every
+//! request/response variant, and both the reassembly path and the outgoing path
+//! encode/decode against it, so no consumer has to invent its own byte parsing.
+
+use wg_2024::network::NodeId;
+use crate::codec_negotiation::Codec;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppMessage {
+    RegisterRequest { client_id: NodeId },
+    RegisterResponse { accepted: bool },
+    ClientListRequest,
+    ClientListResponse { clients: Vec<NodeId> },
+    Content { from: NodeId, to: NodeId, payload: Vec<u8> },
+    // sent uncompressed, before any data fragments for a session, to agree on the
+    // compression codec the rest of the session's fragments will use
+    CodecHandshake { supported: Vec<Codec> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    Empty,
+    UnknownTag(u8),
+    Truncated,
+    Decompression,
+}
+
+impl AppMessage {
+    const TAG_REGISTER_REQUEST: u8 = 0;
+    const TAG_REGISTER_RESPONSE: u8 = 1;
+    const TAG_CLIENT_LIST_REQUEST: u8 = 2;
+    const TAG_CLIENT_LIST_RESPONSE: u8 = 3;
+    const TAG_CONTENT: u8 = 4;
+    const TAG_CODEC_HANDSHAKE: u8 = 5;
+
+    /// Encodes this message to the bytes the fragmenter should split into fragments.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            AppMessage::RegisterRequest { client_id } => {
+                bytes.push(Self::TAG_REGISTER_REQUEST);
+                bytes.push(*client_id);
+            }
+            AppMessage::RegisterResponse { accepted } => {
+                bytes.push(Self::TAG_REGISTER_RESPONSE);
+                bytes.push(*accepted as u8);
+            }
+            AppMessage::ClientListRequest => {
+                bytes.push(Self::TAG_CLIENT_LIST_REQUEST);
+            }
+            AppMessage::ClientListResponse { clients } => {
+                bytes.push(Self::TAG_CLIENT_LIST_RESPONSE);
+                bytes.extend((clients.len() as u32).to_be_bytes());
+                bytes.extend(clients.iter().copied());
+            }
+            AppMessage::Content { from, to, payload } => {
+                bytes.push(Self::TAG_CONTENT);
+                bytes.push(*from);
+                bytes.push(*to);
+                bytes.extend((payload.len() as u32).to_be_bytes());
+                bytes.extend(payload);
+            }
+            AppMessage::CodecHandshake { supported } => {
+                bytes.push(Self::TAG_CODEC_HANDSHAKE);
+                bytes.push(supported.len() as u8);
+                bytes.extend(supported.iter().map(|codec| codec.to_byte()));
+            }
+        }
+        bytes
+    }
+
+    /// Decodes the contiguous bytes of a fully reassembled message, exhaustively
+    /// matching every variant of the schema instead of an ad-hoc `match` on raw bytes.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (&tag, rest) = bytes.split_first().ok_or(DecodeError::Empty)?;
+        match tag {
+            Self::TAG_REGISTER_REQUEST => {
+                let &client_id = rest.first().ok_or(DecodeError::Truncated)?;
+                Ok(AppMessage::RegisterRequest { client_id })
+            }
+            Self::TAG_REGISTER_RESPONSE => {
+                let &accepted = rest.first().ok_or(DecodeError::Truncated)?;
+                Ok(AppMessage::RegisterResponse { accepted: accepted != 0 })
+            }
+            Self::TAG_CLIENT_LIST_REQUEST => Ok(AppMessage::ClientListRequest),
+            Self::TAG_CLIENT_LIST_RESPONSE => {
+                let len_bytes = rest.get(0..4).ok_or(DecodeError::Truncated)?;
+                let count = u32::from_be_bytes(len_bytes.try_into().unwrap()) as usize;
+                let clients = rest.get(4..4 + count).ok_or(DecodeError::Truncated)?.to_vec();
+                Ok(AppMessage::ClientListResponse { clients })
+            }
+            Self::TAG_CONTENT => {
+                let &from = rest.first().ok_or(DecodeError::Truncated)?;
+                let &to = rest.get(1).ok_or(DecodeError::Truncated)?;
+                let len_bytes = rest.get(2..6).ok_or(DecodeError::Truncated)?;
+                let len = u32::from_be_bytes(len_bytes.try_into().unwrap()) as usize;
+                let payload = rest.get(6..6 + len).ok_or(DecodeError::Truncated)?.to_vec();
+                Ok(AppMessage::Content { from, to, payload })
+            }
+            Self::TAG_CODEC_HANDSHAKE => {
+                let &count = rest.first().ok_or(DecodeError::Truncated)?;
+                let codec_bytes = rest.get(1..1 + count as usize).ok_or(DecodeError::Truncated)?;
+                let supported = codec_bytes.iter()
+                    .map(|&byte| Codec::from_byte(byte).ok_or(DecodeError::Truncated))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(AppMessage::CodecHandshake { supported })
+            }
+            unknown => Err(DecodeError::UnknownTag(unknown)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_client_list_response() {
+        let message = AppMessage::ClientListResponse { clients: vec![1, 2, 3] };
+        let bytes = message.encode();
+        assert_eq!(AppMessage::decode(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn round_trips_client_list_response_past_the_old_u8_count_limit() {
+        let clients: Vec<NodeId> = (0..300).map(|id| (id % 256) as NodeId).collect();
+        let message = AppMessage::ClientListResponse { clients };
+        let bytes = message.encode();
+        assert_eq!(AppMessage::decode(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn round_trips_content() {
+        let message = AppMessage::Content { from: 5, to: 9, payload: vec![1, 2, 3, 4] };
+        let bytes = message.encode();
+        assert_eq!(AppMessage::decode(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn decode_empty_is_err() {
+        assert_eq!(AppMessage::decode(&[]), Err(DecodeError::Empty));
+    }
+
+    #[test]
+    fn decode_unknown_tag_is_err() {
+        assert_eq!(AppMessage::decode(&[255]), Err(DecodeError::UnknownTag(255)));
+    }
+
+    #[test]
+    fn round_trips_codec_handshake() {
+        let message = AppMessage::CodecHandshake { supported: vec![Codec::None, Codec::Lz4] };
+        let bytes = message.encode();
+        assert_eq!(AppMessage::decode(&bytes), Ok(message));
+    }
+}
@@ -0,0 +1,123 @@
+//! Per-session compression capability handshake, in the spirit of the capability
+//! handshakes used to negotiate encryption/compression before data flows: before a
+//! session's data fragments are sent, both endpoints exchange the codecs they
+//! support (`AppMessage::CodecHandshake`), deterministically agree on one, and
+//! every fragment of that session is compressed/decompressed with the result.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    None,
+    Lz4,
+    Zstd,
+}
+
+/// Codecs this endpoint supports, in deterministic preference order (most to least
+/// preferred). Both endpoints agree on the same order, so picking the first shared
+/// entry gives a deterministic choice without further coordination.
+pub const SUPPORTED_CODECS: [Codec; 3] = [Codec::Zstd, Codec::Lz4, Codec::None];
+
+impl Codec {
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Lz4 => 1,
+            Codec::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Lz4),
+            2 => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Picks the most preferred codec present in both peers' supported lists, falling
+/// back to `Codec::None` when nothing overlaps.
+pub fn negotiate(remote_supported: &[Codec]) -> Codec {
+    SUPPORTED_CODECS.into_iter()
+        .find(|codec| remote_supported.contains(codec))
+        .unwrap_or(Codec::None)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecompressionError {
+    Corrupt,
+}
+
+/// Compresses `bytes` with the session's negotiated codec, ready for fragmentation.
+pub fn compress(codec: Codec, bytes: &[u8]) -> Vec<u8> {
+    match codec {
+        Codec::None => bytes.to_vec(),
+        Codec::Lz4 => lz4_flex::compress_prepend_size(bytes),
+        Codec::Zstd => zstd::encode_all(bytes, 0).expect("zstd compression never fails on a Vec sink"),
+    }
+}
+
+/// Decompresses the contiguous bytes of a reassembled session with its negotiated codec.
+pub fn decompress(codec: Codec, bytes: &[u8]) -> Result<Vec<u8>, DecompressionError> {
+    match codec {
+        Codec::None => Ok(bytes.to_vec()),
+        Codec::Lz4 => lz4_flex::decompress_size_prepended(bytes).map_err(|_| DecompressionError::Corrupt),
+        Codec::Zstd => zstd::decode_all(bytes).map_err(|_| DecompressionError::Corrupt),
+    }
+}
+
+/// Tracks the codec chosen for each in-flight session, so every fragment belonging
+/// to that `session_id` is compressed/decompressed consistently (the invariant the
+/// handshake exists to establish).
+#[derive(Debug, Default)]
+pub struct SessionCodecs {
+    chosen: RefCell<HashMap<u64, Codec>>,
+}
+
+impl SessionCodecs {
+    pub fn new() -> Self {
+        Self { chosen: RefCell::new(HashMap::new()) }
+    }
+
+    pub fn record(&self, session_id: u64, codec: Codec) {
+        self.chosen.borrow_mut().insert(session_id, codec);
+    }
+
+    /// Falls back to `Codec::None` when no handshake was recorded for the session.
+    pub fn get(&self, session_id: u64) -> Codec {
+        self.chosen.borrow().get(&session_id).copied().unwrap_or(Codec::None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn negotiate_picks_most_preferred_shared_codec() {
+        assert_eq!(negotiate(&[Codec::Lz4, Codec::None]), Codec::Lz4);
+    }
+
+    #[test]
+    fn negotiate_falls_back_to_none_without_overlap() {
+        assert_eq!(negotiate(&[]), Codec::None);
+    }
+
+    #[test]
+    fn session_codecs_defaults_to_none() {
+        let session_codecs = SessionCodecs::new();
+        assert_eq!(session_codecs.get(42), Codec::None);
+        session_codecs.record(42, Codec::Zstd);
+        assert_eq!(session_codecs.get(42), Codec::Zstd);
+    }
+
+    #[test]
+    fn none_codec_round_trips_identity() {
+        let bytes = b"hello".to_vec();
+        let compressed = compress(Codec::None, &bytes);
+        assert_eq!(decompress(Codec::None, &compressed), Ok(bytes));
+    }
+}
@@ -2,11 +2,14 @@ use std::collections::HashMap;
 use crossbeam_channel::{Sender, Receiver};
 use wg_2024::network::NodeId;
 use wg_2024::packet::Packet;
+use crate::app_message::{AppMessage, DecodeError};
+use crate::codec_negotiation::{decompress, negotiate, Codec, SessionCodecs};
 
 struct Listener {
     tx_channel: Sender<Packet>, // this should only transmit packets of all types but PacketType::MsgFragment(Fragment)
-    server_logic_channel: Sender<Packet>, // this should only transmit reassembled messages
-    connected_drones: HashMap<NodeId, Receiver<Packet>>
+    server_logic_channel: Sender<Result<AppMessage, DecodeError>>, // this should only transmit decoded application messages
+    connected_drones: HashMap<NodeId, Receiver<Packet>>,
+    session_codecs: SessionCodecs,
 }
 
 impl Listener {
@@ -18,4 +21,24 @@ impl Listener {
     fn run(&self) {
         todo!()
     }
+
+    /// A `CodecHandshake` for `session_id` arrived ahead of any data fragments:
+    /// negotiate the codec both endpoints support and record it so every later
+    /// fragment of that session is decompressed consistently.
+    fn handle_codec_handshake(&self, session_id: u64, remote_supported: &[Codec]) {
+        let codec = negotiate(remote_supported);
+        self.session_codecs.record(session_id, codec);
+    }
+
+    /// Decompresses the contiguous bytes of a fully reassembled message with the
+    /// session's negotiated codec, then decodes the result and hands the typed
+    /// outcome to server logic, so every consumer matches exhaustively on
+    /// `AppMessage` instead of parsing raw fragment bytes itself.
+    fn deliver_reassembled_message(&self, session_id: u64, bytes: Vec<u8>) {
+        let codec = self.session_codecs.get(session_id);
+        let message = decompress(codec, &bytes)
+            .map_err(|_| DecodeError::Decompression)
+            .and_then(|decompressed| AppMessage::decode(&decompressed));
+        let _ = self.server_logic_channel.send(message);
+    }
 }
\ No newline at end of file
@@ -1,10 +1,16 @@
 use std::collections::HashMap;
+use std::future::Future;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::sync::mpsc;
 use crossbeam_channel::{select, Receiver, Sender};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tokio::task::JoinHandle;
-use wg_2024::network::NodeId;
+use wg_2024::network::{NodeId, SourceRoutingHeader};
 use wg_2024::packet::{Nack, Packet};
+use crate::app_message::AppMessage;
+use crate::codec_negotiation::{compress, negotiate, Codec, SessionCodecs};
+use crate::transmitter::gateway::Gateway;
 use crate::transmitter::network_controller::NetworkController;
 use crate::transmitter::transmission_handler::TransmissionHandler;
 use tokio::time::{sleep, Duration};
@@ -16,16 +22,32 @@ mod gateway;
 #[derive(Debug)]
 enum Command {
     Resend(u64),
-    Confirmed,
+    Confirmed(u64),
+}
+
+/// Outcome of a `Transmitter::broadcast`: which clients fully acked every fragment
+/// and which ones never completed (no known route, or their `TransmissionHandler`
+/// gave up before every fragment was acked).
+#[derive(Debug, Default)]
+pub struct BroadcastReport {
+    pub delivered: Vec<NodeId>,
+    pub unreachable: Vec<NodeId>,
 }
 
 struct Transmitter<'a> {
     receiver_channel: Receiver<Nack>,
-    server_logic_channel: Receiver<Packet>,
+    server_logic_channel: Receiver<AppMessage>, // carries outgoing messages from server logic
     network_controller: NetworkController<'a>,
     transmission_handler: Rc<TransmissionHandler<'a>>,
     command_channel: Sender<Command>,
-    connected_drones: HashMap<NodeId, Receiver<Packet>>
+    connected_drones: HashMap<NodeId, Receiver<Packet>>,
+    gateway: Arc<Gateway>,
+    session_codecs: SessionCodecs,
+    // fed by every spawned TransmissionHandler's routing_error_channel; drained into
+    // network_controller.handle_routing_error so a broken edge self-heals regardless
+    // of which destination's handler discovered it
+    routing_error_sender: Sender<NodeId>,
+    routing_error_receiver: Receiver<NodeId>,
 }
 
 impl<'a> Transmitter<'a> {
@@ -33,4 +55,182 @@ impl<'a> Transmitter<'a> {
     fn new() -> Self {
         todo!()
     }
+
+    /// Negotiates the compression codec for `session_id` from the remote endpoint's
+    /// advertised `CodecHandshake` and records it before any data fragments flow.
+    fn negotiate_session_codec(&self, session_id: u64, remote_supported: &[Codec]) -> Codec {
+        let codec = negotiate(remote_supported);
+        self.session_codecs.record(session_id, codec);
+        codec
+    }
+
+    /// Encodes an outgoing application message and compresses it with the session's
+    /// negotiated codec, producing the bytes the fragmenter splits into `Fragment`s
+    /// — the symmetric counterpart of the reassembly path's decompress-then-decode.
+    fn encode_for_transmission(&self, session_id: u64, message: &AppMessage) -> Vec<u8> {
+        let codec = self.session_codecs.get(session_id);
+        compress(codec, &message.encode())
+    }
+
+    /// Drains every broken hop reported by a `TransmissionHandler`'s
+    /// `routing_error_channel` into the `NetworkController`, so a stale edge is
+    /// dropped and a fresh flood kicked off as soon as forwarding fails anywhere.
+    fn drain_routing_errors(&self) {
+        while let Ok(broken_hop) = self.routing_error_receiver.try_recv() {
+            self.network_controller.handle_routing_error(broken_hop);
+        }
+    }
+
+    /// Spawns a `TransmissionHandler` for `client` on `route`, retaining its real
+    /// `Command` sender so the handler's channel stays open until the handler itself
+    /// finishes — dropping it early would close the channel and make `run()` return
+    /// as if every fragment had been (falsely) acknowledged. The handler's own
+    /// `MAX_RETRANSMISSIONS` bound caps how long it keeps retrying a destination that
+    /// stops making progress, so its task always completes — `run()`'s `bool` says
+    /// whether it actually finished by full delivery or gave up.
+    fn spawn_handler(&self, route: SourceRoutingHeader, message: &'static [Packet]) -> (UnboundedSender<Command>, JoinHandle<bool>) {
+        let (command_tx, command_rx) = unbounded_channel::<Command>();
+        let gateway = Arc::clone(&self.gateway);
+        let routing_error_sender = self.routing_error_sender.clone();
+        let mut handler = TransmissionHandler::new(command_rx, message, route, gateway, Some(routing_error_sender));
+        (command_tx, tokio::spawn(async move { handler.run().await }))
+    }
+
+    /// Fans `message` out to every client the `NetworkController` currently knows of,
+    /// spawning one `TransmissionHandler` per reachable destination — each stamping
+    /// its packets with that destination's own computed source route — so their
+    /// selective-repeat windows progress independently. Clients with no currently
+    /// known route are reported unreachable without spawning a handler for them.
+    /// The returned future resolves once every spawned handler has finished, either
+    /// because its client fully acked its fragments or because it gave up, reporting
+    /// the subset that never completed.
+    pub fn broadcast(&self, message: &'static [Packet]) -> impl Future<Output = BroadcastReport> {
+        let mut in_flight: Vec<(NodeId, UnboundedSender<Command>, JoinHandle<bool>)> = Vec::new();
+        let mut unreachable = Vec::new();
+
+        for client in self.network_controller.known_clients() {
+            let Some(route) = self.network_controller.route_to(client) else {
+                unreachable.push(client);
+                continue;
+            };
+            let (command_tx, handle) = self.spawn_handler(route, message);
+            in_flight.push((client, command_tx, handle));
+        }
+
+        async move {
+            let mut delivered = Vec::new();
+            let mut unreachable = unreachable;
+            for (client, command_tx, handle) in in_flight {
+                // keep the sender alive for the handler's whole lifetime: dropping it
+                // early closes the channel and makes `run()` return as if every
+                // fragment had been (falsely) acknowledged
+                let result = handle.await;
+                drop(command_tx);
+                match result {
+                    Ok(true) => delivered.push(client),
+                    Ok(false) | Err(_) => unreachable.push(client),
+                }
+            }
+            BroadcastReport { delivered, unreachable }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use crossbeam_channel::unbounded;
+    use tokio::sync::mpsc::unbounded_channel;
+    use wg_2024::network::SourceRoutingHeader;
+    use wg_2024::packet::{Fragment, FloodResponse, NodeType, Packet, PacketType};
+    use crate::codec_negotiation::SessionCodecs;
+    use crate::transmitter::gateway::Gateway;
+    use crate::transmitter::network_controller::NetworkController;
+    use crate::transmitter::transmission_handler::TransmissionHandler;
+    use super::*;
+
+    fn make_fragment_packet(fragment_index: u64, total: u64) -> Packet {
+        Packet {
+            pack_type: PacketType::MsgFragment(Fragment {
+                fragment_index,
+                total_n_fragments: total,
+                length: 0,
+                data: [0; 128],
+            }),
+            routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+            session_id: 0,
+        }
+    }
+
+    fn make_transmitter(node_id: NodeId) -> Transmitter<'static> {
+        let (_receiver_tx, receiver_rx) = unbounded::<Nack>();
+        let (_server_logic_tx, server_logic_rx) = unbounded::<AppMessage>();
+        let (drone_tx, drone_rx) = unbounded::<Packet>();
+        // the gateway reports routing errors to this receiver and panics once it is
+        // disconnected, so keep it alive for the rest of the test
+        std::mem::forget(drone_rx);
+        let gateway = Arc::new(Gateway::new(node_id, HashMap::new(), drone_tx));
+        let (_command_tx, command_rx) = unbounded_channel::<Command>();
+        let route = SourceRoutingHeader { hop_index: 0, hops: vec![] };
+        let transmission_handler = Rc::new(TransmissionHandler::new(command_rx, &[], route, Arc::clone(&gateway), None));
+        let network_controller = NetworkController::new(node_id, HashMap::new(), transmission_handler);
+        let (command_channel, _command_rx) = unbounded::<Command>();
+        let (routing_error_sender, routing_error_receiver) = unbounded::<NodeId>();
+        Transmitter {
+            receiver_channel: receiver_rx,
+            server_logic_channel: server_logic_rx,
+            network_controller,
+            transmission_handler: Rc::new(TransmissionHandler::new(
+                unbounded_channel().1,
+                &[],
+                SourceRoutingHeader { hop_index: 0, hops: vec![] },
+                gateway.clone(),
+                None,
+            )),
+            command_channel,
+            connected_drones: HashMap::new(),
+            gateway,
+            session_codecs: SessionCodecs::new(),
+            routing_error_sender,
+            routing_error_receiver,
+        }
+    }
+
+    #[tokio::test]
+    async fn spawned_handler_completes_only_after_the_real_ack_arrives() {
+        let transmitter = make_transmitter(1);
+        let route = SourceRoutingHeader { hop_index: 0, hops: vec![1, 2] };
+        let packets: &'static [Packet] = Box::leak(vec![make_fragment_packet(0, 1)].into_boxed_slice());
+
+        let (command_tx, handle) = transmitter.spawn_handler(route, packets);
+        // proves the sender broadcast() retains is the one actually feeding the
+        // handler, not a throwaway dropped before the handler is ever polled
+        command_tx.send(Command::Confirmed(0)).expect("handler's command channel must still be open");
+        let delivered = handle.await.expect("handler task should finish cleanly once its only fragment is acked");
+        assert!(delivered);
+    }
+
+    #[tokio::test]
+    async fn broadcast_reports_clients_with_no_known_route_as_unreachable() {
+        let transmitter = make_transmitter(1);
+        transmitter.network_controller.handle_flood_response(FloodResponse {
+            flood_id: 0,
+            path_trace: vec![(1, NodeType::Server), (2, NodeType::Client)],
+        });
+        // a disjoint segment: node 4 is a known client, but never traces back to us
+        transmitter.network_controller.handle_flood_response(FloodResponse {
+            flood_id: 1,
+            path_trace: vec![(3, NodeType::Drone), (4, NodeType::Client)],
+        });
+        // an empty fragment list so the reachable client's handler completes
+        // immediately; this test is about routing/unreachable bookkeeping, not
+        // ack-driven completion (covered separately)
+        let packets: &'static [Packet] = &[];
+
+        let report = transmitter.broadcast(packets).await;
+
+        assert_eq!(report.delivered, vec![2]);
+        assert_eq!(report.unreachable, vec![4]);
+    }
 }
\ No newline at end of file
@@ -1,17 +1,278 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
+use crossbeam_channel::Sender;
+use wg_2024::network::{NodeId, SourceRoutingHeader};
+use wg_2024::packet::{FloodRequest, FloodResponse, NodeType, Packet, PacketType};
 use crate::transmitter::transmission_handler::TransmissionHandler;
 
+/// Undirected adjacency graph learned from `FloodResponse`s: for every node we have
+/// heard about, the set of neighbors it reported in some `path_trace`.
+type Topology = HashMap<NodeId, HashSet<NodeId>>;
+
 pub struct NetworkController<'a> {
+    node_id: NodeId,
     transmission_handler: Rc<TransmissionHandler<'a>>,
-
+    neighbors: HashMap<NodeId, Sender<Packet>>,
+    next_flood_id: Cell<u64>,
+    topology: RefCell<Topology>,
+    // NodeType last reported for every node seen in a path_trace, so destinations
+    // can be filtered to actual clients instead of treating every reachable node
+    // (including intermediate drones) as one
+    node_types: RefCell<HashMap<NodeId, NodeType>>,
+    // shortest known hop-path from `node_id` to every reachable node, excluding `node_id` itself
+    routes: RefCell<HashMap<NodeId, Vec<NodeId>>>,
 }
 
 impl<'a> NetworkController<'a> {
-    fn new() -> Self {
-        todo!()
+    pub fn new(
+        node_id: NodeId,
+        neighbors: HashMap<NodeId, Sender<Packet>>,
+        transmission_handler: Rc<TransmissionHandler<'a>>,
+    ) -> Self {
+        Self {
+            node_id,
+            transmission_handler,
+            neighbors,
+            next_flood_id: Cell::new(0),
+            topology: RefCell::new(HashMap::new()),
+            node_types: RefCell::new(HashMap::new()),
+            routes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Starts a new topology discovery round: broadcasts a `FloodRequest` to every
+    /// neighbor, each carrying a fresh `flood_id` and a `path_trace` seeded with
+    /// this server's own id.
+    pub fn start_flooding(&self) {
+        let flood_id = self.next_flood_id.get();
+        self.next_flood_id.set(flood_id + 1);
+        let flood_request = FloodRequest {
+            flood_id,
+            initiator_id: self.node_id,
+            path_trace: vec![(self.node_id, NodeType::Server)],
+        };
+        let packet = Packet {
+            pack_type: PacketType::FloodRequest(flood_request),
+            routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![] },
+            session_id: flood_id,
+        };
+        for channel in self.neighbors.values() {
+            let _ = channel.try_send(packet.clone());
+        }
+    }
+
+    /// A `FloodRequest` reached us: since the server is always a terminal node for
+    /// flooding, we turn it straight into a `FloodResponse` that walks the reverse
+    /// of the accumulated `path_trace`, rather than re-broadcasting further.
+    pub fn build_flood_response(&self, mut flood_request: FloodRequest) -> Packet {
+        flood_request.path_trace.push((self.node_id, NodeType::Server));
+        let hops: Vec<NodeId> = flood_request.path_trace.iter().map(|(id, _)| *id).rev().collect();
+        let flood_response = FloodResponse {
+            flood_id: flood_request.flood_id,
+            path_trace: flood_request.path_trace,
+        };
+        Packet {
+            pack_type: PacketType::FloodResponse(flood_response),
+            routing_header: SourceRoutingHeader { hop_index: 0, hops },
+            session_id: flood_request.flood_id,
+        }
+    }
+
+    /// Folds an incoming `FloodResponse`'s `path_trace` into the adjacency graph and
+    /// recomputes shortest routes from it.
+    pub fn handle_flood_response(&self, flood_response: FloodResponse) {
+        self.merge_path_trace(&flood_response.path_trace);
+        self.recompute_routes();
+    }
+
+    fn merge_path_trace(&self, path_trace: &[(NodeId, NodeType)]) {
+        let mut node_types = self.node_types.borrow_mut();
+        for &(node_id, node_type) in path_trace {
+            node_types.insert(node_id, node_type);
+        }
+
+        let mut topology = self.topology.borrow_mut();
+        for pair in path_trace.windows(2) {
+            let (a, _) = pair[0];
+            let (b, _) = pair[1];
+            topology.entry(a).or_default().insert(b);
+            topology.entry(b).or_default().insert(a);
+        }
+    }
+
+    /// BFS from `node_id` over the learned topology, producing the minimal-hop path
+    /// to every reachable node.
+    fn recompute_routes(&self) {
+        let topology = self.topology.borrow();
+        let mut predecessors: HashMap<NodeId, NodeId> = HashMap::new();
+        let mut visited: HashSet<NodeId> = HashSet::new();
+        let mut queue: VecDeque<NodeId> = VecDeque::new();
+        visited.insert(self.node_id);
+        queue.push_back(self.node_id);
+
+        while let Some(current) = queue.pop_front() {
+            if let Some(neighbors) = topology.get(&current) {
+                for &next in neighbors {
+                    if visited.insert(next) {
+                        predecessors.insert(next, current);
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        let mut routes = HashMap::new();
+        for &node in &visited {
+            if node == self.node_id {
+                continue;
+            }
+            let mut path = vec![node];
+            let mut current = node;
+            while let Some(&pred) = predecessors.get(&current) {
+                if pred == self.node_id {
+                    break;
+                }
+                path.push(pred);
+                current = pred;
+            }
+            path.reverse();
+            routes.insert(node, path);
+        }
+        *self.routes.borrow_mut() = routes;
+    }
+
+    /// Builds the source-routing header to reach `dest`, if a route is currently known.
+    pub fn route_to(&self, dest: NodeId) -> Option<SourceRoutingHeader> {
+        self.routes.borrow().get(&dest).map(|hops_after_self| {
+            let mut hops = vec![self.node_id];
+            hops.extend(hops_after_self.iter().copied());
+            SourceRoutingHeader { hop_index: 0, hops }
+        })
+    }
+
+    /// Every client the flooding round has discovered so far, whether or not a route
+    /// to it is currently known — distinct from `route_to`, whose `None` result for
+    /// one of these ids means that particular client is unreachable right now.
+    pub fn known_clients(&self) -> Vec<NodeId> {
+        self.node_types.borrow().iter()
+            .filter(|(_, node_type)| matches!(node_type, NodeType::Client))
+            .map(|(&node_id, _)| node_id)
+            .collect()
+    }
+
+    /// Called when `Gateway::forward` reports `NackType::ErrorInRouting(n)`: the edge
+    /// to `n` is stale, so drop it from the graph, recompute routes and kick off a
+    /// fresh flood so the topology self-heals.
+    pub fn handle_routing_error(&self, broken_hop: NodeId) {
+        // `n` is the next hop this server's gateway could not reach, so the stale
+        // edge is the one between `node_id` and `n`
+        let mut topology = self.topology.borrow_mut();
+        if let Some(set) = topology.get_mut(&self.node_id) {
+            set.remove(&broken_hop);
+        }
+        if let Some(set) = topology.get_mut(&broken_hop) {
+            set.remove(&self.node_id);
+        }
+        drop(topology);
+        self.recompute_routes();
+        self.start_flooding();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crossbeam_channel::unbounded;
+    use tokio::sync::mpsc::unbounded_channel;
+    use crate::transmitter::gateway::Gateway;
+    use std::sync::Arc;
+
+    fn make_controller(node_id: NodeId) -> NetworkController<'static> {
+        let (_command_tx, command_rx) = unbounded_channel();
+        let (drone_tx, _drone_rx) = unbounded::<Packet>();
+        let gateway = Arc::new(Gateway::new(node_id, HashMap::new(), drone_tx));
+        let route = SourceRoutingHeader { hop_index: 0, hops: vec![] };
+        let transmission_handler = Rc::new(TransmissionHandler::new(command_rx, &[], route, gateway, None));
+        NetworkController::new(node_id, HashMap::new(), transmission_handler)
+    }
+
+    #[test]
+    fn route_to_unknown_destination_is_none() {
+        let controller = make_controller(1);
+        assert_eq!(controller.route_to(42), None);
+    }
+
+    #[test]
+    fn merges_path_trace_and_computes_shortest_route() {
+        let controller = make_controller(1);
+        let flood_response = FloodResponse {
+            flood_id: 0,
+            path_trace: vec![(1, NodeType::Server), (2, NodeType::Drone), (3, NodeType::Client)],
+        };
+        controller.handle_flood_response(flood_response);
+
+        assert_eq!(controller.route_to(2), Some(SourceRoutingHeader { hop_index: 0, hops: vec![1, 2] }));
+        assert_eq!(controller.route_to(3), Some(SourceRoutingHeader { hop_index: 0, hops: vec![1, 2, 3] }));
     }
 
-    fn start_flooding(&self) {
-        todo!()
+    #[test]
+    fn build_flood_response_uses_hop_index_zero() {
+        let controller = make_controller(1);
+        let flood_request = FloodRequest {
+            flood_id: 0,
+            initiator_id: 2,
+            path_trace: vec![(2, NodeType::Client)],
+        };
+        let response = controller.build_flood_response(flood_request);
+        assert_eq!(response.routing_header, SourceRoutingHeader { hop_index: 0, hops: vec![1, 2] });
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn known_clients_excludes_drones() {
+        let controller = make_controller(1);
+        let flood_response = FloodResponse {
+            flood_id: 0,
+            path_trace: vec![(1, NodeType::Server), (2, NodeType::Drone), (3, NodeType::Client)],
+        };
+        controller.handle_flood_response(flood_response);
+
+        assert_eq!(controller.known_clients(), vec![3]);
+    }
+
+    #[test]
+    fn known_clients_includes_unreachable_clients_from_a_disjoint_segment() {
+        let controller = make_controller(1);
+        controller.handle_flood_response(FloodResponse {
+            flood_id: 0,
+            path_trace: vec![(1, NodeType::Server), (2, NodeType::Drone), (3, NodeType::Client)],
+        });
+        // a flood response that never traces back to this server: node 5 is a known
+        // client, but it is not part of this server's connected component
+        controller.handle_flood_response(FloodResponse {
+            flood_id: 1,
+            path_trace: vec![(4, NodeType::Drone), (5, NodeType::Client)],
+        });
+
+        let mut known_clients = controller.known_clients();
+        known_clients.sort();
+        assert_eq!(known_clients, vec![3, 5]);
+        assert!(controller.route_to(3).is_some());
+        assert_eq!(controller.route_to(5), None);
+    }
+
+    #[test]
+    fn handle_routing_error_drops_edge_and_restarts_flood() {
+        let controller = make_controller(1);
+        let flood_response = FloodResponse {
+            flood_id: 0,
+            path_trace: vec![(1, NodeType::Server), (2, NodeType::Drone)],
+        };
+        controller.handle_flood_response(flood_response);
+        assert!(controller.route_to(2).is_some());
+
+        controller.handle_routing_error(2);
+        assert_eq!(controller.route_to(2), None);
+        assert_eq!(controller.next_flood_id.get(), 1);
+    }
+}
@@ -1,148 +1,263 @@
-use std::cell::Cell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
 use std::marker::PhantomData;
-use std::sync::{Arc};
-use std::sync::mpsc::{Receiver};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::task::JoinHandle;
-use tokio::sync::mpsc;
+use crossbeam_channel::Sender as CrossbeamSender;
 use tokio::sync::mpsc::UnboundedReceiver;
-use tokio::time::sleep;
-use wg_2024::packet::Packet;
+use tokio::time::{sleep, Instant};
+use wg_2024::network::{NodeId, SourceRoutingHeader};
+use wg_2024::packet::{NackType, Packet};
 use crate::transmitter::Command;
 use crate::transmitter::gateway::Gateway;
 
+/// Per-fragment bookkeeping for the selective-repeat window: every fragment is
+/// either waiting for a send slot, in flight with a resend deadline, or acked.
+/// `sent_at`/`retransmitted` back the Jacobson/Karn RTT sampling: a fragment that
+/// has been retransmitted can no longer yield a trustworthy RTT sample. `attempts`
+/// counts every send (initial plus retransmits), capping retries so a destination
+/// that can never be reached doesn't retry forever.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FragmentState {
+    Pending,
+    InFlight { sent_at: Instant, deadline: Instant, retransmitted: bool, attempts: u32 },
+    Acked,
+}
+
+const RTO_FLOOR: Duration = Duration::from_millis(200);
+const RTO_CEILING: Duration = Duration::from_secs(30);
+const INITIAL_RTO: Duration = Duration::from_secs(2);
+// once a fragment has been sent this many times with no ack, the destination is
+// treated as unreachable rather than retried at the RTO ceiling forever
+const MAX_RETRANSMISSIONS: u32 = 5;
+
+/// Jacobson/Karn RTO estimation: a smoothed RTT (`srtt`) and its mean deviation
+/// (`rttvar`) are kept per destination and combined into the retransmission
+/// timeout `rto`, which backs off exponentially on timeouts and resets back to the
+/// smoothed estimate as soon as a clean (non-retransmitted) ack arrives.
+#[derive(Debug)]
+struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+}
+
+impl RttEstimator {
+    fn new() -> Self {
+        Self { srtt: None, rttvar: Duration::ZERO, rto: INITIAL_RTO }
+    }
+
+    /// Folds in a clean RTT sample `r`, per RFC 6298:
+    /// `SRTT = (1-α)·SRTT + α·R`, `RTTVAR = (1-β)·RTTVAR + β·|SRTT - R|`, α=1/8, β=1/4.
+    fn on_sample(&mut self, r: Duration) {
+        self.srtt = Some(match self.srtt {
+            None => {
+                self.rttvar = r / 2;
+                r
+            }
+            Some(srtt) => {
+                let delta = if srtt > r { srtt - r } else { r - srtt };
+                self.rttvar = self.rttvar.mul_f64(0.75) + delta.mul_f64(0.25);
+                srtt.mul_f64(0.875) + r.mul_f64(0.125)
+            }
+        });
+        self.rto = (self.srtt.unwrap() + self.rttvar * 4).clamp(RTO_FLOOR, RTO_CEILING);
+    }
+
+    /// Exponential backoff on a timeout-triggered resend; stays in effect until the
+    /// next clean sample calls `on_sample` again.
+    fn on_timeout(&mut self) {
+        self.rto = (self.rto * 2).clamp(RTO_FLOOR, RTO_CEILING);
+    }
+}
+
 #[derive(Debug)]
 pub struct TransmissionHandler<'a> {
     command_channel: UnboundedReceiver<Command>,
     packets: &'a [Packet], // contains the data to transmit
-    window_size: usize,
+    // source route stamped onto every packet before it is forwarded, so a single
+    // handler's packets reach whichever destination it was spawned for
+    route: SourceRoutingHeader,
     window_start: Cell<usize>,
-    timeout: Duration,
+    // per flush, how many Pending fragments get sent...
+    items_in_batch: usize,
+    // ...but InFlight fragments are never allowed to exceed this count
+    batch_count: usize,
+    rtt_estimator: RefCell<RttEstimator>,
     gateway: Arc<Gateway>,
-    fragment_channels: HashMap<u64, mpsc::UnboundedSender<Command>>,
+    fragment_states: RefCell<Vec<FragmentState>>,
+    // reports the broken hop when `Gateway::forward` returns `NackType::ErrorInRouting`,
+    // so the caller's `NetworkController` can drop the stale edge and reflood
+    routing_error_channel: Option<CrossbeamSender<NodeId>>,
+    // set once some fragment has exhausted MAX_RETRANSMISSIONS: the destination is
+    // no longer reachable, so `run()` gives up instead of retrying forever
+    gave_up: Cell<bool>,
     pd: PhantomData<&'a u32>
 }
 
 impl<'a> TransmissionHandler<'a> {
-    fn new(command_channel: UnboundedReceiver<Command>, packets: &'a[Packet], gateway: Arc<Gateway>) -> Self {
+    pub(crate) fn new(
+        command_channel: UnboundedReceiver<Command>,
+        packets: &'a[Packet],
+        route: SourceRoutingHeader,
+        gateway: Arc<Gateway>,
+        routing_error_channel: Option<CrossbeamSender<NodeId>>,
+    ) -> Self {
         Self {
             command_channel,
             packets,
-            window_size: 1,
+            route,
             window_start: Cell::new(0),
-            timeout: Duration::from_secs(2),
+            items_in_batch: 4,
+            batch_count: 8,
+            rtt_estimator: RefCell::new(RttEstimator::new()),
             gateway,
-            fragment_channels: HashMap::new(),
+            fragment_states: RefCell::new(vec![FragmentState::Pending; packets.len()]),
+            routing_error_channel,
+            gave_up: Cell::new(false),
             pd: PhantomData::default() // TODO: remove this when lifetimes are used or no longer needed
         }
     }
 
-    fn on_ack_received(&self) {
-        let previous_start = self.window_start.get();
-        self.window_start.set(previous_start + 1);
+    /// Stamps `fragment_index`'s packet with this handler's destination route and
+    /// forwards it, reporting a broken hop through `routing_error_channel` so the
+    /// topology can self-heal instead of silently retrying against a stale edge.
+    fn send_packet(&self, fragment_index: usize) {
+        let mut packet = self.packets[fragment_index].clone();
+        packet.routing_header = self.route.clone();
+        if let Err(NackType::ErrorInRouting(broken_hop)) = self.gateway.forward(packet) {
+            if let Some(channel) = &self.routing_error_channel {
+                let _ = channel.try_send(broken_hop);
+            }
+        }
     }
 
-    // 'static required to pass self.gateway to tasks
-    async fn run(&'static mut self) {
-        loop {
-            let slice = &self.packets.get(self.window_start.get()..self.packets.len().min(self.window_start.get() + self.window_size));
-            if let Some(ready_to_send) = slice {
-                for (fragment_index, packet) in ready_to_send.iter().enumerate() {
-                    let fragment_index = fragment_index as u64;
-                    let fragment_command_channel = self.fragment_channels.get(&fragment_index);
-                    match fragment_command_channel {
-                        Some(_) => { },
-                        None => {
-                            let (tx, rx) = mpsc::unbounded_channel::<Command>();
-                            self.fragment_channels.insert(fragment_index, tx);
-                            let handle = Self::spawn_task(fragment_index, self.timeout, || {
-                                self.gateway.forward(packet.clone());
-                            }, rx);
-                        }
-                    };
-                }
-                tokio::select! {
-                    command = self.command_channel.recv() => {
-                        println!("received {command:?}");
-                        if let Some(command) = command {
-                            match command {
-                                Command::Confirmed => {
-                                    self.on_ack_received();
-                                },
-                                Command::Resend(fragment_index) => {
-                                    match self.fragment_channels.get(&fragment_index) {
-                                        Some(channel) => { channel.send(Command::Resend(fragment_index)); }
-                                        None => {}
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                // match self.command_channel.recv() {
-                //     Some(command) => {
-                //         println!("received {command:?}");
-                //         match command {
-                //             Command::Confirmed => {
-                //                 self.on_ack_received();
-                //             },
-                //             Command::Resend(fragment_index) => {
-                //                 match self.fragment_channels.get(&fragment_index) {
-                //                     Some(channel) => { channel.send(Command::Resend(fragment_index)); }
-                //                     None => {}
-                //                 }
-                //             }
-                //         }
-                //     },
-                //     None => {
-                //         // "should never happen"
-                //     }
-                // }
+    /// Marks `fragment_index` as acked and advances `window_start` past the longest
+    /// contiguous run of acked fragments (losses/reordering leave later fragments
+    /// acked while the window stays pinned on an earlier, still-outstanding one).
+    fn on_ack_received(&self, fragment_index: u64) {
+        let mut states = self.fragment_states.borrow_mut();
+        if let Some(state) = states.get_mut(fragment_index as usize) {
+            // Karn's rule: only a fragment that was never retransmitted yields a
+            // trustworthy RTT sample.
+            if let FragmentState::InFlight { sent_at, retransmitted: false, .. } = *state {
+                self.rtt_estimator.borrow_mut().on_sample(sent_at.elapsed());
+            }
+            *state = FragmentState::Acked;
+        }
+        let mut window_start = self.window_start.get();
+        while states.get(window_start).copied() == Some(FragmentState::Acked) {
+            window_start += 1;
+        }
+        self.window_start.set(window_start);
+    }
+
+    /// Re-queues a fragment for sending, e.g. in response to an explicit `Command::Resend`.
+    fn mark_pending(&self, fragment_index: u64) {
+        let mut states = self.fragment_states.borrow_mut();
+        if let Some(state) = states.get_mut(fragment_index as usize) {
+            if *state != FragmentState::Acked {
+                *state = FragmentState::Pending;
             }
-            else {
+        }
+    }
+
+    /// Sends as many `Pending` fragments as `items_in_batch` allows, without ever
+    /// pushing the total number of `InFlight` fragments past `batch_count`.
+    fn send_ready_fragments(&self) {
+        let now = Instant::now();
+        let rto = self.rtt_estimator.borrow().rto;
+        let mut states = self.fragment_states.borrow_mut();
+        let in_flight = states.iter().filter(|state| matches!(state, FragmentState::InFlight { .. })).count();
+        let mut send_budget = self.items_in_batch.min(self.batch_count.saturating_sub(in_flight));
+        for (fragment_index, state) in states.iter_mut().enumerate() {
+            if send_budget == 0 {
                 break;
             }
+            if *state == FragmentState::Pending {
+                self.send_packet(fragment_index);
+                *state = FragmentState::InFlight { sent_at: now, deadline: now + rto, retransmitted: false, attempts: 1 };
+                send_budget -= 1;
+            }
         }
     }
 
-    async fn spawn_task<F>(
-        id: u64,
-        timeout: Duration,
-        task_fn: F,
-        mut command_channel: mpsc::UnboundedReceiver<Command>,
-    ) -> JoinHandle<()>
-    where
-        F: Fn() + Send + 'static
-    {
-        tokio::spawn(async move {
-            loop {
-                task_fn();
-                tokio::select! {
-                    _ = sleep(timeout) => {
-                        println!("Task {} timed out!", id);
-                    }
-                    Some(command) = command_channel.recv() => {
-                        println!("task {id}: received command: {:?}", command);
-                        match command {
-                            Command::Resend(_) => {
-                                println!("Processing resend command...");
-                                continue;
-                            }
-                            Command::Confirmed => {
-                                println!("Command confirmed, exiting loop.");
-                                break;
-                            }
-                        }
+    /// Resends only the individual fragments whose deadline has elapsed, rather than
+    /// the whole window, which is the point of selective repeat. Each resend doubles
+    /// the shared RTO (exponential backoff) until a clean ack resets it. A fragment
+    /// that has already been sent `MAX_RETRANSMISSIONS` times is never resent again —
+    /// instead `gave_up` is set, so `run()` stops treating this destination as
+    /// reachable rather than retrying at the RTO ceiling forever.
+    fn resend_expired_fragments(&self) {
+        let now = Instant::now();
+        let mut states = self.fragment_states.borrow_mut();
+        for (fragment_index, state) in states.iter_mut().enumerate() {
+            if let FragmentState::InFlight { deadline, attempts, .. } = state {
+                if *deadline <= now {
+                    if *attempts >= MAX_RETRANSMISSIONS {
+                        self.gave_up.set(true);
+                        continue;
                     }
-                    else => {
-                        println!("Command channel closed. Exiting loop.");
-                        break;
+                    self.send_packet(fragment_index);
+                    let rto = {
+                        let mut estimator = self.rtt_estimator.borrow_mut();
+                        estimator.on_timeout();
+                        estimator.rto
+                    };
+                    *state = FragmentState::InFlight { sent_at: now, deadline: now + rto, retransmitted: true, attempts: *attempts + 1 };
+                }
+            }
+        }
+    }
+
+    /// How long to wait before the next deadline check: the earliest outstanding
+    /// `InFlight` deadline, or the current RTO if nothing is in flight yet.
+    fn next_deadline_wait(&self) -> Duration {
+        let now = Instant::now();
+        self.fragment_states.borrow().iter()
+            .filter_map(|state| match state {
+                FragmentState::InFlight { deadline, .. } => Some(deadline.saturating_duration_since(now)),
+                _ => None,
+            })
+            .min()
+            .unwrap_or(self.rtt_estimator.borrow().rto)
+    }
+
+    fn all_acked(&self) -> bool {
+        self.window_start.get() >= self.packets.len()
+    }
+
+    /// Drives the selective-repeat window to completion. Returns `true` once every
+    /// fragment has been acked, or `false` if the destination became unreachable —
+    /// a fragment exhausted `MAX_RETRANSMISSIONS`, or the command channel closed
+    /// before every fragment was confirmed.
+    pub(crate) async fn run(&mut self) -> bool {
+        loop {
+            if self.all_acked() {
+                return true;
+            }
+            if self.gave_up.get() {
+                return false;
+            }
+
+            self.send_ready_fragments();
+
+            tokio::select! {
+                command = self.command_channel.recv() => {
+                    match command {
+                        Some(Command::Confirmed(fragment_index)) => {
+                            self.on_ack_received(fragment_index);
+                        },
+                        Some(Command::Resend(fragment_index)) => {
+                            self.mark_pending(fragment_index);
+                        },
+                        None => return false,
                     }
                 }
+                _ = sleep(self.next_deadline_wait()) => {
+                    self.resend_expired_fragments();
+                }
             }
-            println!("task {id} finished");
-        })
+        }
     }
 }
 
@@ -156,48 +271,126 @@ mod test {
     use wg_2024::packet::{Ack, Packet, PacketType};
     use crate::transmitter::Command;
     use crate::transmitter::gateway::Gateway;
-    use crate::transmitter::transmission_handler::TransmissionHandler;
+    use crate::transmitter::transmission_handler::{FragmentState, RttEstimator, TransmissionHandler};
 
-    #[test]
-    fn create() {
-        let (command_tx, command_rx) = unbounded_channel::<Command>();
-        let packet = Packet {
+    fn make_packet() -> Packet {
+        Packet {
             pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
             routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![0, 1, 2] },
             session_id: 0,
-        };
-        let packets = vec![packet];
+        }
+    }
+
+    fn make_route() -> SourceRoutingHeader {
+        SourceRoutingHeader { hop_index: 0, hops: vec![0, 1, 2] }
+    }
+
+    #[test]
+    fn create() {
+        let (_command_tx, command_rx) = unbounded_channel::<Command>();
+        let packets = vec![make_packet()];
         let drone_channels = crossbeam_channel::unbounded::<Packet>();
         let gateway = Gateway::new(0, HashMap::new(), drone_channels.0);
         let gateway = Arc::new(gateway);
         let transmission_handler = TransmissionHandler::new(
             command_rx,
             &packets[..],
-            gateway
+            make_route(),
+            gateway,
+            None,
         );
-        println!("{:?}", transmission_handler);
         assert_eq!(transmission_handler.packets.len(), 1);
-        assert_eq!(transmission_handler.timeout, Duration::from_secs(2));
+        assert_eq!(transmission_handler.rtt_estimator.borrow().rto, Duration::from_secs(2));
         assert_eq!(transmission_handler.window_start.get(), 0);
-        assert_eq!(transmission_handler.window_size, 1);
+        assert_eq!(transmission_handler.items_in_batch, 4);
+        assert_eq!(transmission_handler.batch_count, 8);
+        assert_eq!(transmission_handler.fragment_states.borrow().len(), 1);
     }
 
     #[test]
-    fn check_transmission() {
-        let (command_tx, command_rx) = unbounded_channel::<Command>();
-        let packet = Packet {
-            pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
-            routing_header: SourceRoutingHeader { hop_index: 0, hops: vec![0, 1, 2] },
-            session_id: 0,
-        };
-        let packets = vec![packet];
+    fn rtt_estimator_resets_backoff_on_clean_sample() {
+        let mut estimator = RttEstimator::new();
+        estimator.on_timeout();
+        estimator.on_timeout();
+        assert_eq!(estimator.rto, Duration::from_secs(8));
+
+        estimator.on_sample(Duration::from_millis(500));
+        assert!(estimator.rto < Duration::from_secs(8));
+    }
+
+    #[test]
+    fn on_ack_received_advances_window_past_contiguous_acked_prefix() {
+        let (_command_tx, command_rx) = unbounded_channel::<Command>();
+        let packets = vec![make_packet(), make_packet(), make_packet()];
         let drone_channels = crossbeam_channel::unbounded::<Packet>();
-        let gateway = Gateway::new(0, HashMap::new(), drone_channels.0);
-        let gateway = Arc::new(gateway);
+        let gateway = Arc::new(Gateway::new(0, HashMap::new(), drone_channels.0));
+        let transmission_handler = TransmissionHandler::new(command_rx, &packets[..], make_route(), gateway, None);
+
+        transmission_handler.on_ack_received(1);
+        assert_eq!(transmission_handler.window_start.get(), 0);
+
+        transmission_handler.on_ack_received(0);
+        assert_eq!(transmission_handler.window_start.get(), 2);
+
+        transmission_handler.on_ack_received(2);
+        assert_eq!(transmission_handler.window_start.get(), 3);
+    }
+
+    #[test]
+    fn send_ready_fragments_respects_batch_count() {
+        let (_command_tx, command_rx) = unbounded_channel::<Command>();
+        let packets: Vec<Packet> = (0..5).map(|_| make_packet()).collect();
+        let drone_channels = crossbeam_channel::unbounded::<Packet>();
+        let gateway = Arc::new(Gateway::new(0, HashMap::new(), drone_channels.0));
+        let mut transmission_handler = TransmissionHandler::new(command_rx, &packets[..], make_route(), gateway, None);
+        transmission_handler.items_in_batch = 2;
+        transmission_handler.batch_count = 3;
+
+        transmission_handler.send_ready_fragments();
+        transmission_handler.send_ready_fragments();
+        let in_flight = transmission_handler.fragment_states.borrow().iter()
+            .filter(|state| matches!(state, FragmentState::InFlight { .. }))
+            .count();
+        assert_eq!(in_flight, 3);
+    }
+
+    #[test]
+    fn send_packet_reports_broken_hop_through_routing_error_channel() {
+        let (_command_tx, command_rx) = unbounded_channel::<Command>();
+        let packets = vec![make_packet()];
+        let drone_channels = crossbeam_channel::unbounded::<Packet>();
+        // no neighbors registered, so forwarding past hop_index 0 hits a dead end
+        let gateway = Arc::new(Gateway::new(0, HashMap::new(), drone_channels.0));
+        let (error_tx, error_rx) = crossbeam_channel::unbounded::<wg_2024::network::NodeId>();
         let transmission_handler = TransmissionHandler::new(
             command_rx,
             &packets[..],
-            gateway
+            make_route(),
+            gateway,
+            Some(error_tx),
         );
+
+        transmission_handler.send_packet(0);
+
+        assert_eq!(error_rx.try_recv(), Ok(1));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn resend_expired_fragments_gives_up_past_max_retransmissions() {
+        let (_command_tx, command_rx) = unbounded_channel::<Command>();
+        let packets = vec![make_packet()];
+        let drone_channels = crossbeam_channel::unbounded::<Packet>();
+        let gateway = Arc::new(Gateway::new(0, HashMap::new(), drone_channels.0));
+        let transmission_handler = TransmissionHandler::new(command_rx, &packets[..], make_route(), gateway, None);
+        *transmission_handler.fragment_states.borrow_mut() = vec![FragmentState::InFlight {
+            sent_at: tokio::time::Instant::now(),
+            deadline: tokio::time::Instant::now(),
+            retransmitted: true,
+            attempts: super::MAX_RETRANSMISSIONS,
+        }];
+
+        transmission_handler.resend_expired_fragments();
+
+        assert!(transmission_handler.gave_up.get());
+    }
+}